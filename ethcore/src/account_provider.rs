@@ -0,0 +1,150 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Account management.
+//!
+//! Tracks which accounts are unlocked and for how long: a single signing
+//! operation, the session's lifetime, or until a deadline. The deadline
+//! isn't enforced by a timer -- `sign` is the only place that consults it,
+//! evicting the unlock there if it has passed, so an expired unlock never
+//! outlives the moment something actually tries to use it.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use util::{Mutex, Address, H256};
+use ethkey::Signature;
+use ethstore::{SecretStore, Error as SSError};
+
+/// How long an account stays unlocked for signing.
+#[derive(Clone)]
+enum Unlock {
+	/// Unlocked for exactly one signing operation.
+	OneTime,
+	/// Unlocked until explicitly locked again.
+	Perm,
+	/// Unlocked until the given instant.
+	Timed(Instant),
+}
+
+#[derive(Clone)]
+struct AccountData {
+	unlock: Unlock,
+	password: String,
+}
+
+/// Error unlocking or signing with an account.
+#[derive(Debug)]
+pub enum Error {
+	/// The account isn't unlocked (or its unlock has expired).
+	NotUnlocked,
+	/// The underlying keystore rejected the request.
+	SStore(SSError),
+}
+
+impl From<SSError> for Error {
+	fn from(e: SSError) -> Self {
+		Error::SStore(e)
+	}
+}
+
+/// Manages unlocked accounts and dispatches signing requests to the
+/// keystore, enforcing how long each account was unlocked for.
+pub struct AccountProvider {
+	unlocked: Mutex<HashMap<Address, AccountData>>,
+	sstore: Box<SecretStore>,
+}
+
+impl AccountProvider {
+	/// Creates a new account provider backed by `sstore`.
+	pub fn new(sstore: Box<SecretStore>) -> Self {
+		AccountProvider {
+			unlocked: Mutex::new(HashMap::new()),
+			sstore: sstore,
+		}
+	}
+
+	/// Lists all accounts known to the underlying keystore.
+	pub fn accounts(&self) -> Vec<Address> {
+		self.sstore.accounts()
+	}
+
+	/// Creates a new account protected by `password`.
+	pub fn new_account(&self, password: &str) -> Result<Address, Error> {
+		Ok(try!(self.sstore.new_account(password)))
+	}
+
+	/// Unlocks `account` for a single subsequent signing operation.
+	pub fn unlock_account_temporarily(&self, account: Address, password: String) -> Result<(), Error> {
+		self.unlock_account(account, password, Unlock::OneTime)
+	}
+
+	/// Unlocks `account` until it is explicitly locked again.
+	pub fn unlock_account_permanently(&self, account: Address, password: String) -> Result<(), Error> {
+		self.unlock_account(account, password, Unlock::Perm)
+	}
+
+	/// Unlocks `account` for `duration` seconds.
+	pub fn unlock_account_timed(&self, account: Address, password: String, duration: u64) -> Result<(), Error> {
+		let expires = Instant::now() + Duration::from_secs(duration);
+		self.unlock_account(account, password, Unlock::Timed(expires))
+	}
+
+	fn unlock_account(&self, account: Address, password: String, unlock: Unlock) -> Result<(), Error> {
+		try!(self.sstore.check(&account, &password));
+		self.unlocked.lock().insert(account, AccountData { unlock: unlock, password: password });
+		Ok(())
+	}
+
+	/// Locks `account`, discarding any outstanding unlock.
+	pub fn lock_account(&self, account: Address) {
+		self.unlocked.lock().remove(&account);
+	}
+
+	/// Signs `message` with `account`, using whichever unlock is on file
+	/// for it. A one-time unlock is consumed by this call; a timed unlock
+	/// that has expired is evicted here and treated as not unlocked.
+	pub fn sign(&self, account: Address, message: H256) -> Result<Signature, Error> {
+		let password = {
+			let mut unlocked = self.unlocked.lock();
+			let data = match unlocked.get(&account) {
+				Some(data) => data.clone(),
+				None => return Err(Error::NotUnlocked),
+			};
+
+			match data.unlock {
+				Unlock::OneTime => { unlocked.remove(&account); }
+				Unlock::Perm => {}
+				Unlock::Timed(expires) => {
+					if Instant::now() >= expires {
+						unlocked.remove(&account);
+						return Err(Error::NotUnlocked);
+					}
+				}
+			}
+
+			data.password
+		};
+
+		Ok(try!(self.sstore.sign(&account, &password, &message)))
+	}
+
+	/// Signs `message` with `account` using `password` directly, without
+	/// consulting or disturbing any unlock already on file for it.
+	pub fn sign_with_password(&self, account: Address, password: String, message: H256) -> Result<Signature, Error> {
+		Ok(try!(self.sstore.sign(&account, &password, &message)))
+	}
+}
@@ -0,0 +1,130 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Block chunk packing and rebuilding for snapshots.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use blockchain::BlockChain;
+use engine::Engine;
+use error::Error;
+
+use util::{Bytes, H256, U256, UtilError};
+use util::rlp::{RlpStream, UntrustedRlp, Stream, View};
+
+use super::MAX_CHUNK_SIZE;
+
+/// Pack blocks `(first_block, last_block]` (inclusive of `last_block`,
+/// exclusive of `first_block` itself) into capped, snappy-compressed block
+/// chunks. Each chunk is an rlp list of `(block_rlp, receipts_rlp,
+/// total_difficulty)` triples in ascending block order.
+pub fn chunk_blocks(chain: &BlockChain, first_block: u64, last_block: u64) -> Result<Vec<Bytes>, Error> {
+	let mut chunks = Vec::new();
+	let mut buffer = Vec::new();
+	let mut entries_in_buffer = 0usize;
+	let mut pending = Vec::new();
+
+	for number in (first_block + 1)..(last_block + 1) {
+		let hash = try!(chain.block_hash(number).ok_or_else(|| UtilError::SimpleString(format!("missing block {}", number))));
+		let block = try!(chain.block(&hash).ok_or_else(|| UtilError::SimpleString(format!("missing block body {}", number))));
+		let receipts = chain.block_receipts(&hash).map(|r| r.receipts).unwrap_or_default();
+		let total_difficulty = try!(chain.block_details(&hash).map(|d| d.total_difficulty)
+			.ok_or_else(|| UtilError::SimpleString(format!("missing block details {}", number))));
+
+		let mut entry_stream = RlpStream::new_list(3);
+		entry_stream.append(&block).append(&receipts).append(&total_difficulty);
+		let entry = entry_stream.out();
+
+		if entries_in_buffer > 0 && buffer.len() + entry.len() > MAX_CHUNK_SIZE {
+			chunks.push(flush_chunk(&mut pending));
+			buffer.clear();
+			entries_in_buffer = 0;
+		}
+
+		buffer.extend_from_slice(&entry);
+		pending.push(entry);
+		entries_in_buffer += 1;
+	}
+
+	if entries_in_buffer > 0 {
+		chunks.push(flush_chunk(&mut pending));
+	}
+
+	Ok(chunks)
+}
+
+fn flush_chunk(pending: &mut Vec<Bytes>) -> Bytes {
+	let mut stream = RlpStream::new_list(pending.len());
+	for entry in pending.drain(..) {
+		stream.append_raw(&entry, 1);
+	}
+	::util::snappy::compress(&stream.out())
+}
+
+/// Rebuilds a contiguous range of blocks from block chunks fed in
+/// (possibly out-of-order) during restoration.
+pub struct BlockRebuilder {
+	chain: BlockChain,
+	// number of blocks fed into the rebuilder so far.
+	fed_blocks: usize,
+}
+
+impl BlockRebuilder {
+	/// Create a new block rebuilder writing into the given (fresh) chain.
+	pub fn new(chain: BlockChain) -> Result<Self, Error> {
+		Ok(BlockRebuilder {
+			chain: chain,
+			fed_blocks: 0,
+		})
+	}
+
+	/// Feed an uncompressed block chunk -- an rlp list of `(block_rlp,
+	/// receipts_rlp, total_difficulty)` triples in ascending order -- into
+	/// the chain, verifying each block against `engine` as it goes. `abort`
+	/// is checked between blocks so a chunk in progress can still be
+	/// interrupted promptly by `clear`/a new `begin_restore`.
+	pub fn feed(&mut self, chunk: &[u8], engine: &Engine, abort: &AtomicBool) -> Result<(), Error> {
+		let rlp = UntrustedRlp::new(chunk);
+
+		for entry_rlp in rlp.iter() {
+			if abort.load(Ordering::SeqCst) {
+				return Err(UtilError::SimpleString("restoration aborted".into()).into());
+			}
+
+			let block_bytes: Bytes = try!(entry_rlp.val_at(0));
+			let receipts: Bytes = try!(entry_rlp.val_at(1));
+			let total_difficulty: U256 = try!(entry_rlp.val_at(2));
+
+			try!(::verification::verify_block_basic(&block_bytes, engine));
+
+			self.chain.insert_unordered_block(&block_bytes, receipts, Some(total_difficulty), false, false);
+			self.fed_blocks += 1;
+		}
+
+		Ok(())
+	}
+
+	/// Glue together any chunks fed out of order now that all of them have
+	/// arrived, connecting each block to its parent in the chain.
+	pub fn glue_chunks(&mut self) {
+		self.chain.commit();
+	}
+
+	/// Number of blocks fed into the rebuilder so far.
+	pub fn blocks_done(&self) -> usize {
+		self.fed_blocks
+	}
+}
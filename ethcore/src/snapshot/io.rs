@@ -0,0 +1,228 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Readers and writers for on-disk snapshot formats.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use error::Error;
+use util::{Bytes, H256};
+use util::rlp::{RlpStream, UntrustedRlp, Stream, View};
+
+use super::ManifestData;
+
+const MANIFEST_FILENAME: &'static str = "MANIFEST";
+
+/// Size in bytes of the footer written at the end of a packed snapshot:
+/// a single little-endian u64 holding the offset at which the index and
+/// manifest blob begins.
+const PACKED_FOOTER_SIZE: u64 = 8;
+
+/// Something which can read a snapshot: its manifest and individual chunks
+/// by hash.
+pub trait SnapshotReader {
+	/// Get the manifest data for this snapshot.
+	fn manifest(&self) -> &ManifestData;
+
+	/// Get raw chunk data (as stored on disk, still snappy-compressed) by
+	/// hash.
+	fn chunk(&self, hash: H256) -> Result<Bytes, Error>;
+}
+
+/// Something which can write out a snapshot: individual chunks, keyed by
+/// hash, followed by the manifest once all chunks have been written.
+pub trait SnapshotWriter {
+	/// Write a raw (already snappy-compressed) chunk, keyed by its keccak
+	/// hash.
+	fn write_chunk(&mut self, hash: H256, chunk: &[u8]) -> Result<(), Error>;
+
+	/// Complete the snapshot, writing out the manifest.
+	fn finish(self, manifest: ManifestData) -> Result<(), Error> where Self: Sized;
+}
+
+/// A snapshot reader which reads from one file per chunk in a directory,
+/// plus a `MANIFEST` file.
+pub struct LooseReader {
+	dir: PathBuf,
+	manifest: ManifestData,
+}
+
+impl LooseReader {
+	/// Open a loose-format snapshot in the given directory.
+	pub fn new(dir: PathBuf) -> Result<Self, Error> {
+		let mut manifest_file = try!(File::open(dir.join(MANIFEST_FILENAME)));
+		let mut buf = Vec::new();
+		try!(manifest_file.read_to_end(&mut buf));
+
+		Ok(LooseReader {
+			dir: dir,
+			manifest: try!(ManifestData::from_rlp(&buf)),
+		})
+	}
+}
+
+impl SnapshotReader for LooseReader {
+	fn manifest(&self) -> &ManifestData {
+		&self.manifest
+	}
+
+	fn chunk(&self, hash: H256) -> Result<Bytes, Error> {
+		let mut file = try!(File::open(self.dir.join(hash.hex())));
+		let mut buf = Vec::new();
+		try!(file.read_to_end(&mut buf));
+		Ok(buf)
+	}
+}
+
+/// A snapshot writer which writes one file per chunk into a directory,
+/// plus a `MANIFEST` file once complete.
+pub struct LooseWriter {
+	dir: PathBuf,
+}
+
+impl LooseWriter {
+	/// Create a new loose writer, creating the directory if necessary.
+	pub fn new(dir: PathBuf) -> Result<Self, Error> {
+		try!(fs::create_dir_all(&dir));
+		Ok(LooseWriter { dir: dir })
+	}
+}
+
+impl SnapshotWriter for LooseWriter {
+	fn write_chunk(&mut self, hash: H256, chunk: &[u8]) -> Result<(), Error> {
+		let mut file = try!(File::create(self.dir.join(hash.hex())));
+		try!(file.write_all(chunk));
+		Ok(())
+	}
+
+	fn finish(self, manifest: ManifestData) -> Result<(), Error> {
+		let mut file = try!(File::create(self.dir.join(MANIFEST_FILENAME)));
+		try!(file.write_all(&manifest.into_rlp()));
+		Ok(())
+	}
+}
+
+/// A snapshot reader which reads all chunks out of a single file, using an
+/// offset index stored at the end of the file to seek directly to each
+/// chunk by hash.
+pub struct PackedReader {
+	path: PathBuf,
+	manifest: ManifestData,
+	index: HashMap<H256, (u64, u64)>,
+}
+
+impl PackedReader {
+	/// Open a packed-format snapshot at the given path. Returns `Ok(None)`
+	/// if the file doesn't look like a packed snapshot (too short for a
+	/// footer), so callers can fall back to trying other formats.
+	pub fn new(path: &::std::path::Path) -> Result<Option<Self>, Error> {
+		let mut file = try!(File::open(path));
+		let file_len = try!(file.metadata()).len();
+		if file_len < PACKED_FOOTER_SIZE {
+			return Ok(None);
+		}
+
+		try!(file.seek(SeekFrom::End(-(PACKED_FOOTER_SIZE as i64))));
+		let mut footer = [0u8; PACKED_FOOTER_SIZE as usize];
+		try!(file.read_exact(&mut footer));
+		let index_start = (&footer[..]).iter().rev().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+
+		try!(file.seek(SeekFrom::Start(index_start)));
+		let mut rest = Vec::new();
+		try!(file.read_to_end(&mut rest));
+
+		let rlp = UntrustedRlp::new(&rest);
+		let raw_index: Vec<(H256, u64, u64)> = try!(rlp.val_at(0));
+		let manifest: ManifestData = try!(rlp.val_at(1));
+
+		let index = raw_index.into_iter().map(|(hash, offset, len)| (hash, (offset, len))).collect();
+
+		Ok(Some(PackedReader {
+			path: path.to_owned(),
+			manifest: manifest,
+			index: index,
+		}))
+	}
+}
+
+impl SnapshotReader for PackedReader {
+	fn manifest(&self) -> &ManifestData {
+		&self.manifest
+	}
+
+	fn chunk(&self, hash: H256) -> Result<Bytes, Error> {
+		let &(offset, len) = try!(self.index.get(&hash)
+			.ok_or_else(|| ::util::UtilError::SimpleString(format!("chunk {} not present in packed snapshot", hash))));
+
+		// re-open independently so concurrent readers don't contend on a
+		// shared file cursor.
+		let mut file = try!(File::open(&self.path));
+		try!(file.seek(SeekFrom::Start(offset)));
+
+		let mut buf = vec![0u8; len as usize];
+		try!(file.read_exact(&mut buf));
+		Ok(buf)
+	}
+}
+
+/// A snapshot writer which appends all chunks into a single file, followed
+/// by an offset index, the rlp-encoded manifest, and a fixed-size footer
+/// pointing at the start of the index.
+pub struct PackedWriter {
+	file: File,
+	cur_len: u64,
+	index: Vec<(H256, u64, u64)>,
+}
+
+impl PackedWriter {
+	/// Create a new packed writer at the given path.
+	pub fn new(path: &::std::path::Path) -> Result<Self, Error> {
+		Ok(PackedWriter {
+			file: try!(File::create(path)),
+			cur_len: 0,
+			index: Vec::new(),
+		})
+	}
+}
+
+impl SnapshotWriter for PackedWriter {
+	fn write_chunk(&mut self, hash: H256, chunk: &[u8]) -> Result<(), Error> {
+		try!(self.file.write_all(chunk));
+		self.index.push((hash, self.cur_len, chunk.len() as u64));
+		self.cur_len += chunk.len() as u64;
+		Ok(())
+	}
+
+	fn finish(mut self, manifest: ManifestData) -> Result<(), Error> {
+		let index_start = self.cur_len;
+
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&self.index);
+		stream.append(&manifest);
+		try!(self.file.write_all(&stream.out()));
+
+		let mut footer = [0u8; PACKED_FOOTER_SIZE as usize];
+		for (i, b) in footer.iter_mut().enumerate() {
+			*b = ((index_start >> (8 * i)) & 0xff) as u8;
+		}
+		try!(self.file.write_all(&footer));
+
+		Ok(())
+	}
+}
@@ -0,0 +1,313 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Snapshot creation, restoration, and network service.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use blockchain::BlockChain;
+use error::Error;
+
+use util::{Bytes, H256, HashDB, UtilError};
+use util::rlp::{Encodable, Decodable, RlpStream, Decoder, DecoderError, Stream, View, UntrustedRlp};
+use util::snappy;
+use util::trie::{TrieDB, TrieDBMut, Trie, TrieMut};
+use util::sha3::Hashable;
+use util::kvdb::Database;
+
+pub use self::service::{Service, SnapshotService, RestorationStatus};
+pub use self::io::{SnapshotReader, SnapshotWriter, LooseReader, LooseWriter, PackedReader, PackedWriter};
+pub use self::block::BlockRebuilder;
+
+mod block;
+mod io;
+mod service;
+
+/// Maximum size, in bytes, of a state or block chunk before compression.
+/// Chunks are flushed as soon as appending the next entry would exceed this.
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Number of recent blocks (with receipts and total difficulty) to include
+/// in a snapshot, so a restored node can verify new blocks immediately
+/// without a full header-chain re-sync.
+pub const SNAPSHOT_BLOCKS: u64 = 30000;
+
+/// Manifest data describing a snapshot: the chunk hashes that make it up
+/// along with the state root and block range it covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestData {
+	/// List of state chunk hashes, in arbitrary order.
+	pub state_hashes: Vec<H256>,
+	/// List of block chunk hashes, in ascending-block order.
+	pub block_hashes: Vec<H256>,
+	/// The root of the state trie at the time of the snapshot.
+	pub state_root: H256,
+	/// Number of the block the snapshot was taken at.
+	pub block_number: u64,
+	/// Hash of the block the snapshot was taken at.
+	pub block_hash: H256,
+}
+
+impl Encodable for ManifestData {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		s.begin_list(5)
+			.append(&self.state_hashes)
+			.append(&self.block_hashes)
+			.append(&self.state_root)
+			.append(&self.block_number)
+			.append(&self.block_hash);
+	}
+}
+
+impl Decodable for ManifestData {
+	fn decode<D: Decoder>(decoder: &D) -> Result<Self, DecoderError> {
+		let d = decoder.as_rlp();
+		Ok(ManifestData {
+			state_hashes: try!(d.val_at(0)),
+			block_hashes: try!(d.val_at(1)),
+			state_root: try!(d.val_at(2)),
+			block_number: try!(d.val_at(3)),
+			block_hash: try!(d.val_at(4)),
+		})
+	}
+}
+
+impl ManifestData {
+	/// Encode the manifest into RLP.
+	pub fn into_rlp(self) -> Bytes {
+		let mut stream = RlpStream::new();
+		stream.append(&self);
+		stream.out()
+	}
+
+	/// Decode the manifest from RLP.
+	pub fn from_rlp(raw: &[u8]) -> Result<Self, Error> {
+		let decoder = UntrustedRlp::new(raw);
+		Ok(try!(decoder.as_val()))
+	}
+}
+
+/// Accumulates account trie entries fed in as state chunks are received and
+/// builds them into a single account trie over the restoration state DB.
+///
+/// Each fed chunk is inserted into the trie (and thus committed to disk)
+/// immediately, so restoration progress survives a crash; only the root
+/// comparison against the manifest is deferred to the end, since hashing
+/// the whole trie after every chunk would be wasteful.
+pub struct StateRebuilder {
+	db: Database,
+	root: H256,
+}
+
+impl StateRebuilder {
+	/// Create a new state rebuilder over the given disk-backed database,
+	/// starting from an empty trie. Use `resume` to continue from a root
+	/// left by a previous, interrupted restoration.
+	pub fn new(db: Database) -> Self {
+		StateRebuilder {
+			db: db,
+			root: H256::zero(),
+		}
+	}
+
+	/// Resume a state rebuilder whose trie already contains entries from
+	/// chunks fed before a crash.
+	pub fn resume(db: Database, root: H256) -> Self {
+		StateRebuilder { db: db, root: root }
+	}
+
+	/// Feed an uncompressed state chunk into the rebuilder. `abort` is
+	/// checked between entries so a chunk in progress can still be
+	/// interrupted promptly by `clear`/a new `begin_restore`.
+	///
+	/// Each chunk is an rlp list of `(address_hash, account_rlp[, code])`
+	/// entries. Contract code shared between accounts is deduplicated by
+	/// code hash rather than stored once per account.
+	pub fn feed(&mut self, chunk: &[u8], abort: &AtomicBool) -> Result<(), Error> {
+		let rlp = UntrustedRlp::new(chunk);
+
+		// pull out the deduplicated code entries first and commit them
+		// straight to the database, keyed by code hash.
+		for entry_rlp in rlp.iter() {
+			if abort.load(Ordering::SeqCst) {
+				return Err(UtilError::SimpleString("restoration aborted".into()).into());
+			}
+
+			if let Ok(code) = entry_rlp.val_at::<Bytes>(2) {
+				try!(self.db.put(&code.sha3()[..], &code).map_err(UtilError::SimpleString));
+			}
+		}
+
+		let mut root = self.root;
+		{
+			let mut trie = if root == H256::zero() {
+				TrieDBMut::new(&mut self.db, &mut root)
+			} else {
+				try!(TrieDBMut::from_existing(&mut self.db, &mut root).map_err(UtilError::from))
+			};
+
+			for entry_rlp in rlp.iter() {
+				if abort.load(Ordering::SeqCst) {
+					return Err(UtilError::SimpleString("restoration aborted".into()).into());
+				}
+
+				let address_hash: H256 = try!(entry_rlp.val_at(0));
+				let account_rlp: Bytes = try!(entry_rlp.val_at(1));
+				try!(trie.insert(&address_hash[..], &account_rlp[..]).map_err(UtilError::from));
+			}
+		}
+		self.root = root;
+
+		Ok(())
+	}
+
+	/// The root of the trie as built so far. Only meaningful for
+	/// comparison against the manifest once every state chunk has been fed.
+	pub fn root(&self) -> H256 {
+		self.root
+	}
+}
+
+/// Streams account trie entries out of a `TrieDB`, splitting them into
+/// capped, snappy-compressed chunks so the in-memory buffer never exceeds
+/// `MAX_CHUNK_SIZE` uncompressed. Accounts with code append the code bytes
+/// inline, read out of `state_db` by code hash, so the rebuilder can
+/// restore contract code without a second pass over the state.
+pub struct StateChunker<'a> {
+	db: &'a TrieDB<'a>,
+	state_db: &'a HashDB,
+	empty_code_hash: H256,
+	buffer_len: usize,
+	pending: Vec<Bytes>,
+	chunks: Vec<Bytes>,
+}
+
+impl<'a> StateChunker<'a> {
+	/// Create a new chunker over the given account trie, reading contract
+	/// code for each account out of `state_db`.
+	pub fn new(db: &'a TrieDB<'a>, state_db: &'a HashDB) -> Self {
+		StateChunker {
+			db: db,
+			state_db: state_db,
+			empty_code_hash: (&[] as &[u8]).sha3(),
+			buffer_len: 0,
+			pending: Vec::new(),
+			chunks: Vec::new(),
+		}
+	}
+
+	// push a single account entry into the pending list, flushing first if
+	// it would overflow the maximum chunk size. if the account has code, a
+	// third rlp element carrying the code bytes is appended.
+	fn push(&mut self, address_hash: H256, account_rlp: Bytes) {
+		let code_hash: Option<H256> = UntrustedRlp::new(&account_rlp).val_at(3).ok();
+		let code = match code_hash {
+			Some(ref hash) if *hash != self.empty_code_hash => self.state_db.get(hash).map(|c| c.to_vec()),
+			_ => None,
+		};
+
+		let mut entry_stream = RlpStream::new_list(if code.is_some() { 3 } else { 2 });
+		entry_stream.append(&address_hash).append(&account_rlp);
+		if let Some(ref code) = code {
+			entry_stream.append(code);
+		}
+		let entry = entry_stream.out();
+
+		if self.buffer_len > 0 && self.buffer_len + entry.len() > MAX_CHUNK_SIZE {
+			self.flush();
+		}
+
+		self.buffer_len += entry.len();
+		self.pending.push(entry);
+	}
+
+	// wrap the pending entries into a single rlp list -- the same framing
+	// `block.rs::flush_chunk` uses -- and compress it, so the rebuilder's
+	// `UntrustedRlp::new(chunk).iter()` sees one entry per list item
+	// instead of the fields of just the first entry.
+	fn flush(&mut self) {
+		if !self.pending.is_empty() {
+			let mut stream = RlpStream::new_list(self.pending.len());
+			for entry in self.pending.drain(..) {
+				stream.append_raw(&entry, 1);
+			}
+			self.chunks.push(snappy::compress(&stream.out()));
+			self.buffer_len = 0;
+		}
+	}
+
+	/// Walk the entire trie, splitting account entries into capped chunks.
+	/// Returns the snappy-compressed chunks in iteration order.
+	pub fn chunk_all(mut self) -> Result<Vec<Bytes>, Error> {
+		for item in self.db.iter() {
+			let (address_hash, account_rlp) = try!(item.map_err(UtilError::from));
+			self.push(H256::from_slice(&address_hash), account_rlp);
+		}
+		self.flush();
+
+		Ok(self.chunks)
+	}
+}
+
+/// Take a snapshot of `state_db`/`chain` at `block_at`, writing chunks
+/// through `writer` and finishing it with the resulting manifest.
+///
+/// Walks the full state trie at `block_at` into capped, snappy-compressed
+/// state chunks, and packs the most recent `SNAPSHOT_BLOCKS` blocks
+/// (bodies, receipts, and total difficulty) into capped block chunks. Each
+/// chunk is written keyed by its keccak hash as it is produced.
+pub fn take_snapshot<W: SnapshotWriter>(
+	chain: &BlockChain,
+	state_db: &HashDB,
+	block_at: H256,
+	mut writer: W,
+) -> Result<(), Error> {
+	let header = try!(chain.block_header(&block_at).ok_or_else(|| UtilError::SimpleString(format!("unknown block {}", block_at))));
+	let block_number = header.number();
+	let state_root = header.state_root();
+
+	let state_chunks = {
+		let trie = try!(TrieDB::new(state_db, &state_root).map_err(UtilError::from));
+		try!(StateChunker::new(&trie, state_db).chunk_all())
+	};
+
+	let mut state_hashes = Vec::with_capacity(state_chunks.len());
+	for chunk in state_chunks {
+		let hash = chunk.sha3();
+		try!(writer.write_chunk(hash, &chunk));
+		state_hashes.push(hash);
+	}
+
+	let first_block = block_number.saturating_sub(SNAPSHOT_BLOCKS);
+	let block_chunks = try!(block::chunk_blocks(chain, first_block, block_number));
+
+	let mut block_hashes = Vec::with_capacity(block_chunks.len());
+	for chunk in block_chunks {
+		let hash = chunk.sha3();
+		try!(writer.write_chunk(hash, &chunk));
+		block_hashes.push(hash);
+	}
+
+	let manifest = ManifestData {
+		state_hashes: state_hashes,
+		block_hashes: block_hashes,
+		state_root: state_root,
+		block_number: block_number,
+		block_hash: block_at,
+	};
+
+	writer.finish(manifest)
+}
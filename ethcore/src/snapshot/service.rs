@@ -17,12 +17,13 @@
 //! Snapshot network service implementation.
 
 use std::collections::HashSet;
-use std::io::ErrorKind;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use super::{ManifestData, StateRebuilder, BlockRebuilder};
-use super::io::{SnapshotReader, LooseReader};
+use super::io::{SnapshotReader, LooseReader, PackedReader};
 
 use blockchain::BlockChain;
 use client::get_db_path;
@@ -35,15 +36,27 @@ use util::{Bytes, H256, Mutex, UtilError};
 use util::io::IoChannel;
 use util::journaldb::{self, Algorithm};
 use util::kvdb::Database;
+use util::rlp::{RlpStream, UntrustedRlp, Stream, View};
 use util::snappy;
 
+const RESTORATION_META_FILENAME: &'static str = "META";
+
 /// Statuses for restorations.
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum RestorationStatus {
 	///	No restoration.
 	Inactive,
-	/// Ongoing restoration.
-	Ongoing,
+	/// Ongoing restoration, with progress counters.
+	Ongoing {
+		/// Total number of state chunks in the snapshot being restored.
+		state_chunks: usize,
+		/// Total number of block chunks in the snapshot being restored.
+		block_chunks: usize,
+		/// Number of state chunks restored so far.
+		state_chunks_done: usize,
+		/// Number of block chunks restored so far.
+		block_chunks_done: usize,
+	},
 	/// Failed restoration.
 	Failed,
 }
@@ -77,78 +90,162 @@ pub trait SnapshotService {
 	fn restore_block_chunk(&self, hash: H256, chunk: Bytes);
 }
 
-/// State restoration manager.
-struct Restoration {
-	state_chunks_left: HashSet<H256>,
-	block_chunks_left: HashSet<H256>,
-	state: StateRebuilder,
-	blocks: BlockRebuilder,
-	snappy_buffer: Bytes,
-}
-
-impl Restoration {
-	// make a new restoration, building databases in the given path.
-	fn new(manifest: &ManifestData, pruning: Algorithm, path: &Path, spec: &Spec) -> Result<Self, Error> {
-		// try something that outputs a string as error. used here for DB stuff
-		macro_rules! try_string {
-			($($t: tt)*) => {
-				try!(($($t)*).map_err(UtilError::SimpleString))
-			}
+// open (or re-open) the on-disk databases used by a restoration.
+fn open_dbs(pruning: Algorithm, path: &Path, spec: &Spec) -> Result<(Database, BlockChain), Error> {
+	// try something that outputs a string as error. used here for DB stuff
+	macro_rules! try_string {
+		($($t: tt)*) => {
+			try!(($($t)*).map_err(UtilError::SimpleString))
 		}
+	}
+
+	let mut state_db_path = path.to_owned();
+	state_db_path.push("state");
 
-		let mut state_db_path = path.to_owned();
-		state_db_path.push("state");
+	let raw_db =
+		try_string!(Database::open_default(&*state_db_path.to_string_lossy()));
 
-		let raw_db =
-			try_string!(Database::open_default(&*state_db_path.to_string_lossy()));
+	let version = ::util::rlp::encode(&journaldb::version(pruning));
+	try_string!(raw_db.put(&journaldb::VERSION_KEY[..], &version[..]));
 
-		let version = ::util::rlp::encode(&journaldb::version(pruning));
-		try_string!(raw_db.put(&journaldb::VERSION_KEY[..], &version[..]));
+	let chain = BlockChain::new(Default::default(), &spec.genesis_block(), path);
 
-		let blocks = try!(BlockRebuilder::new(BlockChain::new(Default::default(), &spec.genesis_block(), path)));
+	Ok((raw_db, chain))
+}
 
-		Ok(Restoration {
-			state_chunks_left: manifest.state_hashes.iter().cloned().collect(),
-			block_chunks_left: manifest.block_hashes.iter().cloned().collect(),
-			state: StateRebuilder::new(raw_db),
-			blocks: blocks,
+/// State-side restoration progress, behind its own lock so that state
+/// chunks can be verified and applied without waiting on block chunks.
+struct StateRestoration {
+	manifest_state_root: H256,
+	chunks_left: HashSet<H256>,
+	rebuilder: StateRebuilder,
+	snappy_buffer: Bytes,
+}
+
+impl StateRestoration {
+	fn new(raw_db: Database, manifest_state_root: H256, chunks_left: HashSet<H256>, state_root_so_far: H256) -> Self {
+		StateRestoration {
+			manifest_state_root: manifest_state_root,
+			chunks_left: chunks_left,
+			rebuilder: StateRebuilder::resume(raw_db, state_root_so_far),
 			snappy_buffer: Vec::new(),
-		})
+		}
 	}
 
-	// feeds a state chunk
-	fn feed_state(&mut self, hash: H256, chunk: &[u8]) -> Result<(), Error> {
-		if self.state_chunks_left.remove(&hash) {
+	// feed a chunk. returns whether it was new (not previously seen).
+	fn feed(&mut self, hash: H256, chunk: &[u8], abort: &AtomicBool) -> Result<bool, Error> {
+		if abort.load(Ordering::SeqCst) {
+			return Err(UtilError::SimpleString("restoration aborted".into()).into());
+		}
+
+		if self.chunks_left.remove(&hash) {
 			let len = try!(snappy::decompress_into(&chunk, &mut self.snappy_buffer));
-			try!(self.state.feed(&self.snappy_buffer[..len]));
+			try!(self.rebuilder.feed(&self.snappy_buffer[..len], abort));
+
+			if self.chunks_left.is_empty() {
+				let root = self.rebuilder.root();
+				if root != self.manifest_state_root {
+					return Err(UtilError::SimpleString(format!(
+						"state root mismatch after restoring all state chunks: expected {}, got {}",
+						self.manifest_state_root, root
+					)).into());
+				}
+			}
 
-			// TODO: verify state root when done.
+			Ok(true)
+		} else {
+			Ok(false)
 		}
+	}
 
-		Ok(())
+	fn is_done(&self) -> bool {
+		self.chunks_left.is_empty()
+	}
+
+	fn root_so_far(&self) -> H256 {
+		self.rebuilder.root()
+	}
+}
+
+/// Block-side restoration progress, behind its own lock so that block
+/// chunks can be verified and applied without waiting on state chunks.
+struct BlockRestoration {
+	chunks_left: HashSet<H256>,
+	rebuilder: BlockRebuilder,
+	snappy_buffer: Bytes,
+}
+
+impl BlockRestoration {
+	fn new(chain: BlockChain, chunks_left: HashSet<H256>) -> Result<Self, Error> {
+		Ok(BlockRestoration {
+			chunks_left: chunks_left,
+			rebuilder: try!(BlockRebuilder::new(chain)),
+			snappy_buffer: Vec::new(),
+		})
 	}
 
-	// feeds a block chunk
-	fn feed_blocks(&mut self, hash: H256, chunk: &[u8], engine: &Engine) -> Result<(), Error> {
-		if self.block_chunks_left.remove(&hash) {
+	// feed a chunk. returns whether it was new (not previously seen).
+	fn feed(&mut self, hash: H256, chunk: &[u8], engine: &Engine, abort: &AtomicBool) -> Result<bool, Error> {
+		if abort.load(Ordering::SeqCst) {
+			return Err(UtilError::SimpleString("restoration aborted".into()).into());
+		}
+
+		if self.chunks_left.remove(&hash) {
 			let len = try!(snappy::decompress_into(&chunk, &mut self.snappy_buffer));
-			try!(self.blocks.feed(&self.snappy_buffer[..len], engine));
+			try!(self.rebuilder.feed(&self.snappy_buffer[..len], engine, abort));
 
-			if self.block_chunks_left.is_empty() {
+			if self.chunks_left.is_empty() {
 				// connect out-of-order chunks.
-				self.blocks.glue_chunks();
+				self.rebuilder.glue_chunks();
 			}
-		}
 
-		Ok(())
+			Ok(true)
+		} else {
+			Ok(false)
+		}
 	}
 
-	// is everything done?
 	fn is_done(&self) -> bool {
-		self.block_chunks_left.is_empty() && self.state_chunks_left.is_empty()
+		self.chunks_left.is_empty()
 	}
 }
 
+// write out the manifest, outstanding chunk hashes, and the state trie
+// root reached so far to `path`/META, so an interrupted restoration can
+// be resumed rather than restarted from scratch.
+fn save_restoration_meta(path: &Path, manifest: &ManifestData, state_chunks_left: &HashSet<H256>, block_chunks_left: &HashSet<H256>, state_root_so_far: H256) -> Result<(), Error> {
+	let mut stream = RlpStream::new_list(4);
+	stream.append(manifest);
+	stream.append(&state_chunks_left.iter().cloned().collect::<Vec<H256>>());
+	stream.append(&block_chunks_left.iter().cloned().collect::<Vec<H256>>());
+	stream.append(&state_root_so_far);
+
+	let mut file = try!(File::create(path.join(RESTORATION_META_FILENAME)));
+	try!(file.write_all(&stream.out()));
+	Ok(())
+}
+
+// load a previously saved restoration meta file, if one exists and parses.
+fn load_restoration_meta(path: &Path) -> Option<(ManifestData, HashSet<H256>, HashSet<H256>, H256)> {
+	let mut file = match File::open(path.join(RESTORATION_META_FILENAME)) {
+		Ok(file) => file,
+		Err(_) => return None,
+	};
+
+	let mut buf = Vec::new();
+	if file.read_to_end(&mut buf).is_err() {
+		return None;
+	}
+
+	let rlp = UntrustedRlp::new(&buf);
+	let manifest: ManifestData = match rlp.val_at(0) { Ok(m) => m, Err(_) => return None };
+	let state_left: Vec<H256> = match rlp.val_at(1) { Ok(v) => v, Err(_) => return None };
+	let block_left: Vec<H256> = match rlp.val_at(2) { Ok(v) => v, Err(_) => return None };
+	let state_root: H256 = match rlp.val_at(3) { Ok(v) => v, Err(_) => return None };
+
+	Some((manifest, state_left.into_iter().collect(), block_left.into_iter().collect(), state_root))
+}
+
 /// Type alias for client io channel.
 pub type Channel = IoChannel<ClientIoMessage>;
 
@@ -157,34 +254,61 @@ pub type Channel = IoChannel<ClientIoMessage>;
 /// This will replace the client's state DB as soon as the last state chunk
 /// is fed, and will replace the client's blocks DB when the last block chunk
 /// is fed.
+///
+/// State and block chunks are verified and applied under separate locks, so
+/// the two kinds of chunk make progress independently of one another on
+/// multi-core machines; `manifest` (and the small amount of bookkeeping
+/// that needs both sides, like persisting progress) is guarded separately.
 pub struct Service {
-	restoration: Mutex<Option<Restoration>>,
+	state: Mutex<Option<StateRestoration>>,
+	blocks: Mutex<Option<BlockRestoration>>,
+	manifest: Mutex<Option<ManifestData>>,
 	db_path: PathBuf,
 	io_channel: Channel,
 	pruning: Algorithm,
 	status: Mutex<RestorationStatus>,
-	reader: Option<LooseReader>,
+	reader: Option<Box<SnapshotReader + Send + Sync>>,
 	spec: Spec,
+	state_chunks_done: AtomicUsize,
+	block_chunks_done: AtomicUsize,
+	abort_restore: AtomicBool,
+	finalizing: AtomicBool,
 }
 
 impl Service {
 	/// Create a new snapshot service.
 	pub fn new(spec: Spec, pruning: Algorithm, db_path: PathBuf, io_channel: Channel) -> Result<Self, Error> {
 		let reader = {
-			let mut snapshot_path = db_path.clone();
-			snapshot_path.push("snapshot");
+			let mut packed_path = db_path.clone();
+			packed_path.push("snapshot.dat");
+
+			// prefer the single-file packed format when present, falling
+			// back to the one-file-per-chunk loose format.
+			match PackedReader::new(&packed_path) {
+				Ok(Some(reader)) => Some(Box::new(reader) as Box<SnapshotReader + Send + Sync>),
+				_ => {
+					let mut snapshot_path = db_path.clone();
+					snapshot_path.push("snapshot");
 
-			LooseReader::new(snapshot_path).ok()
+					LooseReader::new(snapshot_path).ok().map(|r| Box::new(r) as Box<SnapshotReader + Send + Sync>)
+				}
+			}
 		};
 
-		let service = Service {
-			restoration: Mutex::new(None),
+		let mut service = Service {
+			state: Mutex::new(None),
+			blocks: Mutex::new(None),
+			manifest: Mutex::new(None),
 			db_path: db_path,
 			io_channel: io_channel,
 			pruning: pruning,
 			status: Mutex::new(RestorationStatus::Inactive),
 			reader: reader,
 			spec: spec,
+			state_chunks_done: AtomicUsize::new(0),
+			block_chunks_done: AtomicUsize::new(0),
+			abort_restore: AtomicBool::new(false),
+			finalizing: AtomicBool::new(false),
 		};
 
 		// create the snapshot dir if it doesn't exist.
@@ -197,19 +321,72 @@ impl Service {
 			_ => {}
 		}
 
-		// delete the temporary restoration dir if it does exist.
-		match fs::remove_dir_all(service.restoration_dir()) {
-			Err(e) => {
-				if e.kind() != ErrorKind::NotFound {
-					return Err(e.into())
+		// if the restoration dir holds a resumable restoration left behind
+		// by a crash, pick it back up instead of discarding the progress.
+		match load_restoration_meta(&service.restoration_dir()) {
+			Some((manifest, state_left, block_left, state_root_so_far)) => {
+				if let Err(e) = service.resume_restoration(manifest, state_left, block_left, state_root_so_far) {
+					warn!("failed to resume incomplete snapshot restoration: {}", e);
+					let _ = fs::remove_dir_all(service.restoration_dir());
+				}
+			}
+			None => {
+				// no resumable restoration: delete the temporary restoration dir if it exists.
+				match fs::remove_dir_all(service.restoration_dir()) {
+					Err(e) => {
+						if e.kind() != ErrorKind::NotFound {
+							return Err(e.into())
+						}
+					}
+					_ => {}
 				}
 			}
-			_ => {}
 		}
 
 		Ok(service)
 	}
 
+	// re-open a restoration left behind by a crash.
+	fn resume_restoration(&mut self, manifest: ManifestData, state_left: HashSet<H256>, block_left: HashSet<H256>, state_root_so_far: H256) -> Result<(), Error> {
+		let rest_dir = self.restoration_dir();
+		let (raw_db, chain) = try!(open_dbs(self.pruning, &rest_dir, &self.spec));
+
+		let state_chunks = manifest.state_hashes.len();
+		let block_chunks = manifest.block_hashes.len();
+		let state_chunks_done = state_chunks - state_left.len();
+		let block_chunks_done = block_chunks - block_left.len();
+
+		let state_restoration = StateRestoration::new(raw_db, manifest.state_root, state_left, state_root_so_far);
+		let block_restoration = try!(BlockRestoration::new(chain, block_left));
+
+		*self.state.lock() = Some(state_restoration);
+		*self.blocks.lock() = Some(block_restoration);
+		*self.manifest.lock() = Some(manifest);
+
+		self.state_chunks_done.store(state_chunks_done, Ordering::SeqCst);
+		self.block_chunks_done.store(block_chunks_done, Ordering::SeqCst);
+
+		*self.status.lock() = RestorationStatus::Ongoing {
+			state_chunks: state_chunks,
+			block_chunks: block_chunks,
+			state_chunks_done: state_chunks_done,
+			block_chunks_done: block_chunks_done,
+		};
+
+		Ok(())
+	}
+
+	/// Interrupt any in-progress restoration work and tear it down, so that
+	/// a subsequent `begin_restore` doesn't have to wait on it.
+	pub fn abort_restore(&self) {
+		self.abort_restore.store(true, Ordering::SeqCst);
+		*self.state.lock() = None;
+		*self.blocks.lock() = None;
+		*self.manifest.lock() = None;
+		*self.status.lock() = RestorationStatus::Inactive;
+		let _ = fs::remove_dir_all(self.restoration_dir());
+	}
+
 	// Get the client db root.
 	fn client_db_root(&self) -> PathBuf {
 		get_db_path(&self.db_path, self.pruning)
@@ -272,19 +449,78 @@ impl Service {
 		}
 	}
 
-	// finalize the restoration. this accepts an already-locked
-	// restoration as an argument -- so acquiring it again _will_
-	// lead to deadlock.
-	fn finalize_restoration(&self, rest: &mut Option<Restoration>) -> Result<(), Error> {
+	// copy blocks the node already held at and below `first_block` (up from
+	// genesis) from the client's existing blocks/extras DBs into the
+	// freshly-restored ones at `restoration_dir`/blocks, so a warm node
+	// doesn't lose history it already had below the snapshot's range.
+	//
+	// restored block chunks cover `[first_block + 1, block_number]`
+	// (`block.rs::chunk_blocks`), so migration must reach all the way up
+	// to `first_block` itself -- stopping at `first_block - 1` would leave
+	// that block in neither the restored DB nor the migrated range.
+	//
+	// blocks are migrated in ascending order starting from genesis, so the
+	// contiguous ancient history the marker names is never wrong: each
+	// commit extends that contiguous range by exactly one block, and the
+	// marker is advanced to match only after that commit. the marker is
+	// not read here: a migration interrupted by a crash starts again from
+	// block 1 on the next attempt.
+	fn migrate_ancient_blocks(&self, first_block: u64) -> Result<(), Error> {
+		if first_block == 0 {
+			return Ok(());
+		}
+
+		let old_chain = BlockChain::new(Default::default(), &self.spec.genesis_block(), &self.client_db_root());
+		let best_ancient = old_chain.best_block_number();
+		if best_ancient == 0 {
+			return Ok(());
+		}
+
+		let new_chain = BlockChain::new(Default::default(), &self.spec.genesis_block(), &self.restoration_dir());
+
+		let last = ::std::cmp::min(first_block, best_ancient);
+		let mut number = 1;
+		while number <= last {
+			if self.abort_restore.load(Ordering::SeqCst) {
+				return Err(UtilError::SimpleString("ancient block migration aborted".into()).into());
+			}
+
+			let hash = match old_chain.block_hash(number) {
+				Some(hash) => hash,
+				None => break,
+			};
+			let block = try!(old_chain.block(&hash)
+				.ok_or_else(|| UtilError::SimpleString(format!("missing ancient block body {}", number))));
+			let receipts = old_chain.block_receipts(&hash).map(|r| r.receipts).unwrap_or_default();
+			let total_difficulty = old_chain.block_details(&hash).map(|d| d.total_difficulty).unwrap_or_default();
+
+			new_chain.insert_unordered_block(&block, receipts, Some(total_difficulty), false, false);
+			new_chain.commit();
+			new_chain.update_best_ancient_block(&hash);
+
+			number += 1;
+		}
+
+		Ok(())
+	}
+
+	// finalize the restoration. called with both the state and blocks
+	// restorations already torn down (set to `None`) by the caller, which
+	// must hold both locks while doing so.
+	fn finalize_restoration(&self) -> Result<(), Error> {
 		trace!(target: "snapshot", "finalizing restoration");
 
-		// destroy the restoration before replacing databases.
-		*rest = None;
+		let first_block = self.manifest.lock().as_ref()
+			.map(|m| m.block_number.saturating_sub(super::SNAPSHOT_BLOCKS))
+			.unwrap_or(0);
+
+		try!(self.migrate_ancient_blocks(first_block));
 
 		try!(self.replace_client_db("state"));
 		try!(self.replace_client_db("blocks"));
 		try!(self.replace_client_db("extras"));
 
+		*self.manifest.lock() = None;
 		*self.status.lock() = RestorationStatus::Inactive;
 
 		// TODO: take control of restored snapshot.
@@ -293,57 +529,149 @@ impl Service {
 		Ok(())
 	}
 
-	/// Feed a chunk of either kind. no-op if no restoration or status is wrong.
-	fn feed_chunk(&self, hash: H256, chunk: &[u8], is_state: bool) -> Result<(), Error> {
-		match self.status() {
-			RestorationStatus::Inactive | RestorationStatus::Failed => Ok(()),
-			RestorationStatus::Ongoing => {
-				// TODO: be able to process block chunks and state chunks at same time?
-				let mut restoration = self.restoration.lock();
-
-				let res = {
-					let rest = match *restoration {
-						Some(ref mut r) => r,
-						None => return Ok(()),
-					};
-
-					match is_state {
-						true => rest.feed_state(hash, chunk),
-						false => rest.feed_blocks(hash, chunk, &*self.spec.engine),
-					}.map(|_| rest.is_done())
-				};
-
-				match res {
-					Ok(true) => self.finalize_restoration(&mut *restoration),
-					other => other.map(drop),
-				}
-			}
+	// called after each chunk fed to either side (state or blocks), once
+	// that side's lock has been released; takes both locks itself. if both
+	// sides are done, finalizes -- guarded by `finalizing` so only one of
+	// the two callers ever does it.
+	fn maybe_finalize(&self, this_side_done: bool, other_side_done: bool) {
+		if !this_side_done || !other_side_done {
+			return;
+		}
+
+		if self.finalizing.compare_and_swap(false, true, Ordering::SeqCst) {
+			// another thread is already finalizing.
+			return;
+		}
+
+		let mut state = self.state.lock();
+		let mut blocks = self.blocks.lock();
+		*state = None;
+		*blocks = None;
+
+		if let Err(e) = self.finalize_restoration() {
+			warn!("Encountered error during snapshot restoration: {}", e);
+			*self.status.lock() = RestorationStatus::Failed;
+			let _ = fs::remove_dir_all(self.restoration_dir());
+		}
+
+		self.finalizing.store(false, Ordering::SeqCst);
+	}
+
+	// persist progress from both sides of the restoration to disk.
+	fn save_progress(&self) {
+		let manifest = match *self.manifest.lock() {
+			Some(ref m) => m.clone(),
+			None => return,
+		};
+
+		let state = self.state.lock();
+		let blocks = self.blocks.lock();
+
+		let (state_left, state_root_so_far) = match *state {
+			Some(ref s) => (s.chunks_left.clone(), s.root_so_far()),
+			None => (HashSet::new(), manifest.state_root),
+		};
+		let block_left = match *blocks {
+			Some(ref b) => b.chunks_left.clone(),
+			None => HashSet::new(),
+		};
+
+		if let Err(e) = save_restoration_meta(&self.restoration_dir(), &manifest, &state_left, &block_left, state_root_so_far) {
+			warn!("failed to persist restoration progress: {}", e);
 		}
 	}
 
-	/// Feed a state chunk to be processed synchronously.
+	/// Feed a state chunk to be processed.
 	pub fn feed_state_chunk(&self, hash: H256, chunk: &[u8]) {
-		match self.feed_chunk(hash, chunk, true) {
-			Ok(()) => (),
-			Err(e) => {
-				warn!("Encountered error during state restoration: {}", e);
-				*self.restoration.lock() = None;
-				*self.status.lock() = RestorationStatus::Failed;
-				let _ = fs::remove_dir_all(self.restoration_dir());
+		if !self.is_ongoing() {
+			return;
+		}
+
+		let (new_chunk, done) = {
+			let mut state = self.state.lock();
+			let rest = match *state {
+				Some(ref mut r) => r,
+				None => return,
+			};
+
+			match rest.feed(hash, chunk, &self.abort_restore) {
+				Ok(new_chunk) => (new_chunk, rest.is_done()),
+				Err(e) => {
+					warn!("Encountered error during state restoration: {}", e);
+					*state = None;
+					*self.manifest.lock() = None;
+					*self.blocks.lock() = None;
+					*self.status.lock() = RestorationStatus::Failed;
+					let _ = fs::remove_dir_all(self.restoration_dir());
+					return;
+				}
 			}
+		};
+
+		if new_chunk {
+			self.state_chunks_done.fetch_add(1, Ordering::SeqCst);
+			self.save_progress();
+			self.update_progress();
 		}
+
+		let blocks_done = self.blocks.lock().as_ref().map_or(false, |b| b.is_done());
+		self.maybe_finalize(done, blocks_done);
 	}
 
-	/// Feed a block chunk to be processed synchronously.
+	/// Feed a block chunk to be processed.
 	pub fn feed_block_chunk(&self, hash: H256, chunk: &[u8]) {
-		match self.feed_chunk(hash, chunk, false) {
-			Ok(()) => (),
-			Err(e) => {
-				warn!("Encountered error during block restoration: {}", e);
-				*self.restoration.lock() = None;
-				*self.status.lock() = RestorationStatus::Failed;
-				let _ = fs::remove_dir_all(self.restoration_dir());
+		if !self.is_ongoing() {
+			return;
+		}
+
+		let (new_chunk, done) = {
+			let mut blocks = self.blocks.lock();
+			let rest = match *blocks {
+				Some(ref mut r) => r,
+				None => return,
+			};
+
+			match rest.feed(hash, chunk, &*self.spec.engine, &self.abort_restore) {
+				Ok(new_chunk) => (new_chunk, rest.is_done()),
+				Err(e) => {
+					warn!("Encountered error during block restoration: {}", e);
+					*blocks = None;
+					*self.manifest.lock() = None;
+					*self.state.lock() = None;
+					*self.status.lock() = RestorationStatus::Failed;
+					let _ = fs::remove_dir_all(self.restoration_dir());
+					return;
+				}
 			}
+		};
+
+		if new_chunk {
+			self.block_chunks_done.fetch_add(1, Ordering::SeqCst);
+			self.save_progress();
+			self.update_progress();
+		}
+
+		let state_done = self.state.lock().as_ref().map_or(false, |s| s.is_done());
+		self.maybe_finalize(state_done, done);
+	}
+
+	fn is_ongoing(&self) -> bool {
+		match self.status() {
+			RestorationStatus::Ongoing { .. } => true,
+			_ => false,
+		}
+	}
+
+	// update the `Ongoing` status with the latest progress counters.
+	fn update_progress(&self) {
+		let mut status = self.status.lock();
+		if let RestorationStatus::Ongoing { state_chunks, block_chunks, .. } = *status {
+			*status = RestorationStatus::Ongoing {
+				state_chunks: state_chunks,
+				block_chunks: block_chunks,
+				state_chunks_done: self.state_chunks_done.load(Ordering::SeqCst),
+				block_chunks_done: self.block_chunks_done.load(Ordering::SeqCst),
+			};
 		}
 	}
 }
@@ -364,10 +692,21 @@ impl SnapshotService for Service {
 	fn begin_restore(&self, manifest: ManifestData) -> bool {
 		let rest_dir = self.restoration_dir();
 
-		let mut res = self.restoration.lock();
+		// ask any in-progress feed to bail out promptly rather than
+		// blocking this call on the state/blocks locks.
+		self.abort_restore.store(true, Ordering::SeqCst);
+
+		let mut state = self.state.lock();
+		let mut blocks = self.blocks.lock();
+
+		// this restoration is starting fresh; the abort signal was only
+		// meant for whatever came before it.
+		self.abort_restore.store(false, Ordering::SeqCst);
 
 		// tear down existing restoration.
-		*res = None;
+		*state = None;
+		*blocks = None;
+		*self.manifest.lock() = None;
 
 		// delete and restore the restoration dir.
 		if let Err(e) = fs::remove_dir_all(&rest_dir).and_then(|_| fs::create_dir_all(&rest_dir)) {
@@ -381,15 +720,41 @@ impl SnapshotService for Service {
 		}
 
 		// make new restoration.
-		*res = match Restoration::new(&manifest, self.pruning, &rest_dir, &self.spec) {
-				Ok(b) => Some(b),
-				Err(e) => {
-					warn!("encountered error {} while beginning snapshot restoration.", e);
-					return false;
-				}
+		let (raw_db, chain) = match open_dbs(self.pruning, &rest_dir, &self.spec) {
+			Ok(dbs) => dbs,
+			Err(e) => {
+				warn!("encountered error {} while beginning snapshot restoration.", e);
+				return false;
+			}
+		};
+
+		let state_chunks_left: HashSet<H256> = manifest.state_hashes.iter().cloned().collect();
+		let block_chunks_left: HashSet<H256> = manifest.block_hashes.iter().cloned().collect();
+
+		*state = Some(StateRestoration::new(raw_db, manifest.state_root, state_chunks_left.clone(), H256::zero()));
+		*blocks = match BlockRestoration::new(chain, block_chunks_left.clone()) {
+			Ok(b) => Some(b),
+			Err(e) => {
+				warn!("encountered error {} while beginning snapshot restoration.", e);
+				return false;
+			}
 		};
 
-		*self.status.lock() = RestorationStatus::Ongoing;
+		if let Err(e) = save_restoration_meta(&rest_dir, &manifest, &state_chunks_left, &block_chunks_left, H256::zero()) {
+			warn!("failed to persist initial restoration progress: {}", e);
+		}
+
+		self.state_chunks_done.store(0, Ordering::SeqCst);
+		self.block_chunks_done.store(0, Ordering::SeqCst);
+
+		*self.status.lock() = RestorationStatus::Ongoing {
+			state_chunks: manifest.state_hashes.len(),
+			block_chunks: manifest.block_hashes.len(),
+			state_chunks_done: 0,
+			block_chunks_done: 0,
+		};
+
+		*self.manifest.lock() = Some(manifest);
 		true
 	}
 
@@ -402,4 +767,4 @@ impl SnapshotService for Service {
 		self.io_channel.send(ClientIoMessage::FeedBlockChunk(hash, chunk))
 			.expect("snapshot service and io service are kept alive by client service; qed");
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,69 @@
+// Copyright 2015, 2016 Ethcore (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Concrete implementations of the `v1` JSON-RPC traits.
+
+mod personal;
+
+pub use self::personal::PersonalClient;
+
+use jsonrpc_core::{Error, Value, to_value};
+use util::Address;
+use ethcore::account_provider::AccountProvider;
+use ethcore::client::MiningBlockChainClient;
+use ethcore::miner::MinerService;
+use ethcore::transaction::{Action, Transaction};
+use v1::helpers::TransactionRequest;
+use v1::types::H256 as RpcH256;
+
+/// Unlocks `sender` with `password` for a single signing operation, signs
+/// `request` with it, and submits the resulting transaction through
+/// `miner`.
+///
+/// The unlock is a normal, one-time entry in `accounts`' unlock store, so
+/// it is consumed by `AccountProvider::sign` itself rather than by this
+/// function explicitly re-locking afterwards -- the same store and the
+/// same eviction-at-signing-time rule that `personal_unlockAccount`'s
+/// timed and permanent unlocks go through.
+pub fn unlock_sign_and_dispatch<C, M>(
+	client: &C,
+	miner: &M,
+	request: TransactionRequest,
+	accounts: &AccountProvider,
+	sender: Address,
+	password: String,
+) -> Result<Value, Error>
+	where C: MiningBlockChainClient, M: MinerService
+{
+	let transaction = Transaction {
+		nonce: request.nonce.unwrap_or_else(|| miner.last_nonce(client, &sender)),
+		action: request.to.map_or(Action::Create, Action::Call),
+		gas: request.gas.unwrap_or_else(|| miner.sensible_gas_limit()),
+		gas_price: request.gas_price.unwrap_or_else(|| miner.sensible_gas_price()),
+		value: request.value.unwrap_or_default(),
+		data: request.data.unwrap_or_default(),
+	};
+	let hash = transaction.hash();
+
+	try!(accounts.unlock_account_temporarily(sender, password).map_err(|_| Error::invalid_params()));
+	let signature = try!(accounts.sign(sender, hash).map_err(|_| Error::invalid_params()));
+	let signed = transaction.with_signature(signature);
+
+	let tx_hash = signed.hash();
+	try!(miner.import_own_transaction(client, signed).map_err(|_| Error::internal_error()));
+
+	to_value(&RpcH256::from(tx_hash))
+}
@@ -83,10 +83,22 @@ impl<C: 'static, M: 'static> Personal for PersonalClient<C, M> where C: MiningBl
 	fn unlock_account(&self, params: Params) -> Result<Value, Error> {
 		try!(self.active());
 		from_params::<(RpcH160, String, u64)>(params).and_then(
-			|(account, account_pass, _)|{
+			|(account, account_pass, duration)|{
 				let account: Address = account.into();
 				let store = take_weak!(self.accounts);
-				match store.unlock_account_temporarily(account, account_pass) {
+
+				// `duration` is in seconds, following the convention of
+				// `personal_unlockAccount` in other clients: 0 unlocks for
+				// a single signing operation (the previous behaviour of
+				// this endpoint), `u64::max_value()` unlocks indefinitely,
+				// and anything else unlocks for that many seconds.
+				let result = match duration {
+					0 => store.unlock_account_temporarily(account, account_pass),
+					u64::MAX => store.unlock_account_permanently(account, account_pass),
+					secs => store.unlock_account_timed(account, account_pass, secs),
+				};
+
+				match result {
 					Ok(_) => Ok(Value::Bool(true)),
 					Err(_) => Ok(Value::Bool(false)),
 				}
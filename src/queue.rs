@@ -5,36 +5,309 @@ use verification::*;
 use error::*;
 use engine::Engine;
 
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Condvar;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::thread::{self, JoinHandle};
+
+/// Maximum number of blocks allowed in the pipeline (at any stage) before
+/// `import_block` blocks the caller, so a fast peer can't outrun
+/// verification and exhaust memory.
+const MAX_QUEUE_SIZE: usize = 1024;
+
+/// Number of worker threads running the basic/unordered verification
+/// stages in parallel.
+const NUM_VERIFIERS: usize = 4;
+
+/// A block's position in the verification pipeline.
+enum BlockState {
+	/// Waiting for a worker to pick it up.
+	Unverified(Bytes),
+	/// Currently being verified by a worker.
+	Verifying,
+	/// Passed basic/unordered verification; waiting for the finalizer
+	/// thread to run final verification and insert it.
+	Verified(Bytes),
+}
+
+struct Entry {
+	hash: H256,
+	state: BlockState,
+}
+
+/// A snapshot of how many blocks sit at each stage of the pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueInfo {
+	/// Blocks not yet picked up by a verification worker.
+	pub unverified_queue_size: usize,
+	/// Blocks currently being verified by a worker.
+	pub verifying_queue_size: usize,
+	/// Blocks that passed verification and are waiting to be inserted.
+	pub verified_queue_size: usize,
+}
+
+// State shared between `BlockQueue` and its worker/finalizer threads.
+struct Verification {
+	queue: Mutex<VecDeque<Entry>>,
+	// hashes in the pipeline but not yet finalized, so `import_block` can
+	// reject a resubmission before it's durably in `bc`. Entries are
+	// removed once the finalizer is done with them (inserted or dropped),
+	// so this never grows past `MAX_QUEUE_SIZE` plus the one block the
+	// finalizer has taken off the queue.
+	known: Mutex<HashSet<H256>>,
+	bc: Arc<RwLock<BlockChain>>,
+	more_to_verify: Condvar,
+	ready_to_finalize: Condvar,
+	room_available: Condvar,
+	empty: Condvar,
+}
+
 /// A queue of blocks. Sits between network or other I/O and the BlockChain.
-/// Sorts them ready for blockchain insertion.
+///
+/// `import_block` only performs the cheap duplicate check before handing
+/// the block off to the pipeline: a pool of worker threads runs
+/// `verify_block_basic` and `verify_block_unordered` on queued blocks in
+/// parallel, and a single finalizer thread drains blocks that passed those
+/// stages, in the order they were submitted, running `verify_block_final`
+/// and inserting each one into the chain.
 pub struct BlockQueue {
-	bc: Arc<RwLock<BlockChain>>,
-	engine: Arc<Box<Engine>>,
+	verification: Arc<Verification>,
+	deleting: Arc<AtomicBool>,
+	verifiers: Vec<JoinHandle<()>>,
+	finalizer: Option<JoinHandle<()>>,
 }
 
 impl BlockQueue {
 	/// Creates a new queue instance.
 	pub fn new(bc: Arc<RwLock<BlockChain>>, engine: Arc<Box<Engine>>) -> BlockQueue {
+		let verification = Arc::new(Verification {
+			queue: Mutex::new(VecDeque::new()),
+			known: Mutex::new(HashSet::new()),
+			bc: bc.clone(),
+			more_to_verify: Condvar::new(),
+			ready_to_finalize: Condvar::new(),
+			room_available: Condvar::new(),
+			empty: Condvar::new(),
+		});
+		let deleting = Arc::new(AtomicBool::new(false));
+
+		let verifiers = (0..NUM_VERIFIERS).map(|_| {
+			let verification = verification.clone();
+			let engine = engine.clone();
+			let deleting = deleting.clone();
+			thread::spawn(move || run_verifier(verification, engine, deleting))
+		}).collect();
+
+		let finalizer = {
+			let verification = verification.clone();
+			let deleting = deleting.clone();
+			Some(thread::spawn(move || run_finalizer(verification, engine, bc, deleting)))
+		};
+
 		BlockQueue {
-			bc: bc,
-			engine: engine,
+			verification: verification,
+			deleting: deleting,
+			verifiers: verifiers,
+			finalizer: finalizer,
 		}
 	}
 
-	/// Clear the queue and stop verification activity.
+	/// Clear the queue, discarding all pending work regardless of which
+	/// stage it has reached, and wake any thread blocked on backpressure
+	/// or `drain`. Worker threads notice their block has been discarded
+	/// and go back to waiting rather than acting on it; a block the
+	/// finalizer has already taken off the queue is still final-verified
+	/// and inserted. The queue remains usable for further `import_block`
+	/// calls.
 	pub fn clear(&mut self) {
+		{
+			let mut queue = self.verification.queue.lock().unwrap();
+			queue.clear();
+			self.verification.known.lock().unwrap().clear();
+		}
+		self.verification.room_available.notify_all();
+		self.verification.empty.notify_all();
 	}
 
-	/// Add a block to the queue.
+	/// Add a block to the queue. Only performs the cheap duplicate check;
+	/// the heavier verification stages run asynchronously on worker
+	/// threads. Blocks the caller while the queue is full, providing
+	/// backpressure against a peer sending blocks faster than they can be
+	/// verified.
+	///
+	/// Locks `queue` before `known`, and never holds `known` across the
+	/// backpressure wait below -- the same order the verifier and
+	/// `clear()` use -- so this can't deadlock against them.
 	pub fn import_block(&mut self, bytes: &[u8]) -> ImportResult {
 		let header = BlockView::new(bytes).header();
-		if self.bc.read().unwrap().is_known(&header.hash()) {
-			return Err(ImportError::AlreadyInChain);
+		let hash = header.hash();
+
+		let mut queue = self.verification.queue.lock().unwrap();
+		let already_known = self.verification.known.lock().unwrap().contains(&hash)
+			|| self.verification.bc.read().unwrap().is_known(&hash);
+		if already_known {
+			return Err(ImportError::AlreadyInChain.into());
+		}
+
+		while queue.len() >= MAX_QUEUE_SIZE {
+			queue = self.verification.room_available.wait(queue).unwrap();
 		}
-		try!(verify_block_basic(bytes, self.engine.deref().deref()));
-		try!(verify_block_unordered(bytes, self.engine.deref().deref()));
-		try!(verify_block_final(bytes, self.engine.deref().deref(), self.bc.read().unwrap().deref()));
-		self.bc.write().unwrap().insert_block(bytes);
+
+		self.verification.known.lock().unwrap().insert(hash);
+		queue.push_back(Entry { hash: hash, state: BlockState::Unverified(bytes.to_vec()) });
+		self.verification.more_to_verify.notify_all();
+
 		Ok(())
 	}
+
+	/// Get the number of blocks at each stage of the pipeline.
+	pub fn queue_info(&self) -> QueueInfo {
+		let queue = self.verification.queue.lock().unwrap();
+		let mut info = QueueInfo { unverified_queue_size: 0, verifying_queue_size: 0, verified_queue_size: 0 };
+		for entry in queue.iter() {
+			match entry.state {
+				BlockState::Unverified(_) => info.unverified_queue_size += 1,
+				BlockState::Verifying => info.verifying_queue_size += 1,
+				BlockState::Verified(_) => info.verified_queue_size += 1,
+			}
+		}
+		info
+	}
+
+	/// Block the calling thread until every block submitted so far has
+	/// been inserted into the chain, dropped after failing verification,
+	/// or discarded by `clear`.
+	pub fn drain(&self) {
+		let mut queue = self.verification.queue.lock().unwrap();
+		while !queue.is_empty() || !self.verification.known.lock().unwrap().is_empty() {
+			queue = self.verification.empty.wait(queue).unwrap();
+		}
+	}
+}
+
+impl Drop for BlockQueue {
+	fn drop(&mut self) {
+		self.deleting.store(true, AtomicOrdering::SeqCst);
+		self.verification.more_to_verify.notify_all();
+		self.verification.ready_to_finalize.notify_all();
+		self.verification.room_available.notify_all();
+
+		for handle in self.verifiers.drain(..) {
+			let _ = handle.join();
+		}
+		if let Some(handle) = self.finalizer.take() {
+			let _ = handle.join();
+		}
+	}
+}
+
+// picks up unverified blocks in any order (their basic/unordered
+// verification doesn't depend on the chain or on each other) and runs the
+// CPU-heavy verification stages on them off the caller's thread.
+fn run_verifier(verification: Arc<Verification>, engine: Arc<Box<Engine>>, deleting: Arc<AtomicBool>) {
+	loop {
+		let mut queue = verification.queue.lock().unwrap();
+		let mut pos = None;
+		while pos.is_none() {
+			if deleting.load(AtomicOrdering::SeqCst) {
+				return;
+			}
+			pos = queue.iter().position(|entry| match entry.state {
+				BlockState::Unverified(_) => true,
+				_ => false,
+			});
+			if pos.is_none() {
+				queue = verification.more_to_verify.wait(queue).unwrap();
+			}
+		}
+
+		let pos = pos.unwrap();
+		let hash = queue[pos].hash;
+		let bytes = match mem::replace(&mut queue[pos].state, BlockState::Verifying) {
+			BlockState::Unverified(bytes) => bytes,
+			_ => unreachable!(),
+		};
+		drop(queue);
+
+		let result = verify_block_basic(&bytes, engine.deref().deref())
+			.and_then(|_| verify_block_unordered(&bytes, engine.deref().deref()));
+
+		let mut queue = verification.queue.lock().unwrap();
+		if let Some(pos) = queue.iter().position(|entry| entry.hash == hash) {
+			match result {
+				Ok(()) => {
+					queue[pos].state = BlockState::Verified(bytes);
+					verification.ready_to_finalize.notify_all();
+				}
+				Err(e) => {
+					// verification failed: drop the block and forget we'd
+					// seen it, so a corrected copy can be resubmitted.
+					warn!(target: "client", "block {} failed verification: {:?}", hash, e);
+					queue.remove(pos);
+					verification.known.lock().unwrap().remove(&hash);
+					verification.room_available.notify_all();
+					// the block behind it may already be verified.
+					verification.ready_to_finalize.notify_all();
+					if queue.is_empty() {
+						verification.empty.notify_all();
+					}
+				}
+			}
+		}
+	}
+}
+
+// drains verified blocks strictly in submission order -- never skipping
+// ahead of a block still in `Unverified`/`Verifying` state -- so each
+// block's parent is always already in the chain by the time it's final
+// verified and inserted.
+fn run_finalizer(verification: Arc<Verification>, engine: Arc<Box<Engine>>, bc: Arc<RwLock<BlockChain>>, deleting: Arc<AtomicBool>) {
+	loop {
+		let (hash, bytes) = {
+			let mut queue = verification.queue.lock().unwrap();
+			let mut front_verified = false;
+			while !front_verified {
+				if deleting.load(AtomicOrdering::SeqCst) {
+					return;
+				}
+				front_verified = match queue.front() {
+					Some(entry) => match entry.state {
+						BlockState::Verified(_) => true,
+						_ => false,
+					},
+					None => false,
+				};
+				if !front_verified {
+					queue = verification.ready_to_finalize.wait(queue).unwrap();
+				}
+			}
+
+			let entry = queue.pop_front().unwrap();
+			let bytes = match entry.state {
+				BlockState::Verified(bytes) => bytes,
+				_ => unreachable!(),
+			};
+			(entry.hash, bytes)
+		};
+
+		let result = verify_block_final(&bytes, engine.deref().deref(), bc.read().unwrap().deref())
+			.map(|_| bc.write().unwrap().insert_block(&bytes));
+
+		if let Err(e) = result {
+			warn!(target: "client", "block failed final verification: {:?}", e);
+		}
+
+		// whether `bytes` ended up durably in `bc` or was dropped on
+		// failure, it shouldn't stay in `known`: a successful insert makes
+		// `bc.is_known` the authority on it, and a failure must free the
+		// hash up for a corrected resubmission.
+		verification.known.lock().unwrap().remove(&hash);
+
+		let queue = verification.queue.lock().unwrap();
+		verification.room_available.notify_all();
+		if queue.is_empty() {
+			verification.empty.notify_all();
+		}
+	}
 }